@@ -0,0 +1,455 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
+
+use image::{imageops, RgbImage};
+use memmap2::Mmap;
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_output::{Transform, WlOutput};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::protocol::{wl_shm, wl_shm::WlShm};
+use wayland_client::{EventQueue, Main};
+use wayland_protocols::wlr::unstable::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use crate::region::Region;
+use crate::shm;
+
+/// `buffer_done` only exists from this version of the screencopy protocol on.
+const BUFFER_DONE_SINCE: u32 = 3;
+/// `copy_with_damage` was added in version 2.
+pub const COPY_WITH_DAMAGE_SINCE: u32 = 2;
+
+/// Parameters of a wl_shm buffer offered by the compositor for a frame.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameFormat {
+    pub format: wl_shm::Format,
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameState {
+    Failed,
+    Ready,
+}
+
+#[derive(Default)]
+struct FrameEvents {
+    formats: Vec<FrameFormat>,
+    buffer_done: bool,
+    y_invert: bool,
+    state: Option<FrameState>,
+}
+
+/// A completed copy of an output, still backed by the shared memory it was written to.
+pub struct FrameCopy {
+    pub frame_format: FrameFormat,
+    pub y_invert: bool,
+    mmap: Mmap,
+}
+
+/// A frame requested from the compositor, along with the buffer it is copied into once the
+/// compositor has described it. Dropping it destroys the protocol objects, so they are
+/// released on every return path rather than only on success.
+struct PendingFrame {
+    frame: Main<ZwlrScreencopyFrameV1>,
+    events: Rc<RefCell<FrameEvents>>,
+    buffer: Option<FrameBuffer>,
+}
+
+struct FrameBuffer {
+    frame_format: FrameFormat,
+    file: File,
+    shm_pool: Main<WlShmPool>,
+    buffer: Main<WlBuffer>,
+}
+
+impl Drop for PendingFrame {
+    fn drop(&mut self) {
+        if let Some(buffer) = &self.buffer {
+            buffer.buffer.destroy();
+            buffer.shm_pool.destroy();
+        }
+        self.frame.destroy();
+    }
+}
+
+/// What to capture: an output, or the `region` of it given in output-local logical
+/// coordinates.
+pub struct CaptureRequest<'a> {
+    pub output: &'a WlOutput,
+    pub region: Option<Region>,
+}
+
+/// Capture several outputs through wlr-screencopy into shared memory, returning one frame
+/// per request in the same order.
+///
+/// Every frame is requested before any is waited on, so the compositor can copy them all in
+/// the same repaint instead of one roundtrip after another. The shared memory of all frames
+/// is held at once in exchange.
+///
+/// `overlay_cursor` asks for the pointer to be drawn into the frames. It is only a request:
+/// the protocol has no way to report whether it was honoured, and compositors that cannot
+/// composite the cursor into the capture simply leave it out.
+///
+/// With `wait_for_damage` the copies are made with `copy_with_damage`, so they reflect the
+/// next frame the compositor commits rather than the current one. This blocks until
+/// something on each output changes, and is ignored on screencopy bound below v2.
+pub fn capture_outputs(
+    event_queue: &mut EventQueue,
+    screencopy_manager: &Main<ZwlrScreencopyManagerV1>,
+    shm: &Main<WlShm>,
+    requests: &[CaptureRequest],
+    overlay_cursor: bool,
+    wait_for_damage: bool,
+) -> Result<Vec<FrameCopy>, Box<dyn Error>> {
+    let overlay_cursor = overlay_cursor as i32;
+    let version = screencopy_manager.as_ref().version();
+    let mut frames: Vec<_> = requests
+        .iter()
+        .map(|request| request_frame(screencopy_manager, request, overlay_cursor))
+        .collect();
+
+    // Before v3 the compositor announces a single buffer and never sends buffer_done, so
+    // the first buffer event is all we are going to get.
+    let described = |events: &FrameEvents| {
+        events.buffer_done || (version < BUFFER_DONE_SINCE && !events.formats.is_empty())
+    };
+    loop {
+        for pending in &frames {
+            if pending.events.borrow().state == Some(FrameState::Failed) {
+                return Err("Compositor failed to prepare the frame".into());
+            }
+        }
+        if frames
+            .iter()
+            .all(|pending| described(&pending.events.borrow()))
+        {
+            break;
+        }
+        event_queue.dispatch(&mut (), |_, _, _| {})?;
+    }
+
+    for pending in &mut frames {
+        let frame_format = match pending
+            .events
+            .borrow()
+            .formats
+            .iter()
+            .find(|frame_format| is_supported(frame_format.format))
+        {
+            Some(frame_format) => *frame_format,
+            None => return Err("Compositor offered no supported shm format".into()),
+        };
+
+        let size = pool_size(&frame_format)?;
+        let file = shm::create_shm_file(size as u64)?;
+        let shm_pool = shm.create_pool(file.as_raw_fd(), size);
+        let buffer = shm_pool.create_buffer(
+            0,
+            frame_format.width as i32,
+            frame_format.height as i32,
+            frame_format.stride as i32,
+            frame_format.format,
+        );
+        if wait_for_damage && version >= COPY_WITH_DAMAGE_SINCE {
+            pending.frame.copy_with_damage(&buffer);
+        } else {
+            pending.frame.copy(&buffer);
+        }
+        pending.buffer = Some(FrameBuffer {
+            frame_format,
+            file,
+            shm_pool,
+            buffer,
+        });
+    }
+
+    // Frames become ready in whatever order the compositor gets to them.
+    while !frames
+        .iter()
+        .all(|pending| pending.events.borrow().state.is_some())
+    {
+        event_queue.dispatch(&mut (), |_, _, _| {})?;
+    }
+    if frames
+        .iter()
+        .any(|pending| pending.events.borrow().state == Some(FrameState::Failed))
+    {
+        return Err("Compositor failed to copy the frame".into());
+    }
+
+    frames
+        .iter()
+        .map(|pending| {
+            let buffer = pending.buffer.as_ref().unwrap();
+            let mmap = unsafe { Mmap::map(&buffer.file)? };
+            shm::advise_sequential_read(&mmap);
+            Ok(FrameCopy {
+                frame_format: buffer.frame_format,
+                y_invert: pending.events.borrow().y_invert,
+                mmap,
+            })
+        })
+        .collect()
+}
+
+fn request_frame(
+    screencopy_manager: &Main<ZwlrScreencopyManagerV1>,
+    request: &CaptureRequest,
+    overlay_cursor: i32,
+) -> PendingFrame {
+    let frame = match request.region {
+        Some(region) => screencopy_manager.capture_output_region(
+            overlay_cursor,
+            request.output,
+            region.x,
+            region.y,
+            region.width,
+            region.height,
+        ),
+        None => screencopy_manager.capture_output(overlay_cursor, request.output),
+    };
+
+    let events = Rc::new(RefCell::new(FrameEvents::default()));
+    frame.quick_assign({
+        let events = events.clone();
+        move |_, event, _| {
+            let mut events = events.borrow_mut();
+            match event {
+                zwlr_screencopy_frame_v1::Event::Buffer {
+                    format,
+                    width,
+                    height,
+                    stride,
+                } => events.formats.push(FrameFormat {
+                    format,
+                    width,
+                    height,
+                    stride,
+                }),
+                zwlr_screencopy_frame_v1::Event::BufferDone => events.buffer_done = true,
+                zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                    events.y_invert = flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert)
+                }
+                zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+                    events.state = Some(FrameState::Ready)
+                }
+                zwlr_screencopy_frame_v1::Event::Failed => events.state = Some(FrameState::Failed),
+                _ => {}
+            }
+        }
+    });
+
+    PendingFrame {
+        frame,
+        events,
+        buffer: None,
+    }
+}
+
+/// Size in bytes of a pool holding one buffer of `frame_format`.
+///
+/// wl_shm sizes, dimensions and strides are all `i32` on the wire, so anything larger is
+/// rejected up front rather than wrapping around.
+fn pool_size(frame_format: &FrameFormat) -> Result<i32, Box<dyn Error>> {
+    let FrameFormat {
+        width,
+        height,
+        stride,
+        ..
+    } = *frame_format;
+
+    if width == 0 || height == 0 {
+        return Err(format!("Compositor offered an empty {}x{} buffer", width, height).into());
+    }
+    if (stride as u64) < width as u64 * 4 {
+        return Err(format!(
+            "Compositor offered a stride of {} bytes for a {} pixel wide buffer",
+            stride, width
+        )
+        .into());
+    }
+
+    let size = stride as u64 * height as u64;
+    if width > i32::MAX as u32 || height > i32::MAX as u32 || size > i32::MAX as u64 {
+        return Err(format!(
+            "A {}x{} frame needs {} MiB, more than a wl_shm pool can hold ({} MiB)",
+            width,
+            height,
+            size.div_ceil(1 << 20),
+            i32::MAX as u64 >> 20
+        )
+        .into());
+    }
+    Ok(size as i32)
+}
+
+fn is_supported(format: wl_shm::Format) -> bool {
+    matches!(
+        format,
+        wl_shm::Format::Argb8888
+            | wl_shm::Format::Xrgb8888
+            | wl_shm::Format::Abgr8888
+            | wl_shm::Format::Xbgr8888
+    )
+}
+
+impl FrameCopy {
+    /// Convert the copied pixels into an upright RGB image, undoing any y-inversion and the
+    /// `transform` of the output it was captured from.
+    pub fn to_rgb_image(&self, transform: Option<Transform>) -> RgbImage {
+        let FrameFormat {
+            format,
+            width,
+            height,
+            stride,
+        } = self.frame_format;
+
+        // wl_shm formats are little-endian, so Xrgb8888 is laid out as B, G, R, X in memory.
+        let bgr = matches!(format, wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888);
+
+        let mut image = RgbImage::new(width, height);
+        for (y, row) in self.mmap.chunks_exact(stride as usize).enumerate() {
+            for (x, pixel) in row.chunks_exact(4).take(width as usize).enumerate() {
+                let rgb = if bgr {
+                    [pixel[2], pixel[1], pixel[0]]
+                } else {
+                    [pixel[0], pixel[1], pixel[2]]
+                };
+                image.put_pixel(x as u32, y as u32, image::Rgb(rgb));
+            }
+        }
+
+        if self.y_invert {
+            imageops::flip_vertical_in_place(&mut image);
+        }
+        undo_transform(image, transform)
+    }
+}
+
+/// The EXIF orientation telling a viewer to apply what [`undo_transform`] would, or `None`
+/// when the image is already upright.
+pub fn exif_orientation(transform: Option<Transform>) -> Option<u16> {
+    match transform {
+        Some(Transform::_90) => Some(6),
+        Some(Transform::_180) => Some(3),
+        Some(Transform::_270) => Some(8),
+        Some(Transform::Flipped) => Some(2),
+        Some(Transform::Flipped90) => Some(5),
+        Some(Transform::Flipped180) => Some(4),
+        Some(Transform::Flipped270) => Some(7),
+        _ => None,
+    }
+}
+
+/// Screencopy hands back the framebuffer, which the compositor has already rotated and
+/// flipped by the output transform. Apply the inverse to get the image as laid out in the
+/// compositor space.
+fn undo_transform(image: RgbImage, transform: Option<Transform>) -> RgbImage {
+    match transform {
+        Some(Transform::_90) => imageops::rotate90(&image),
+        Some(Transform::_180) => imageops::rotate180(&image),
+        Some(Transform::_270) => imageops::rotate270(&image),
+        Some(Transform::Flipped) => imageops::flip_horizontal(&image),
+        Some(Transform::Flipped90) => imageops::flip_horizontal(&imageops::rotate90(&image)),
+        Some(Transform::Flipped180) => imageops::flip_vertical(&image),
+        Some(Transform::Flipped270) => imageops::flip_horizontal(&imageops::rotate270(&image)),
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_format(width: u32, height: u32, stride: u32) -> FrameFormat {
+        FrameFormat {
+            format: wl_shm::Format::Xrgb8888,
+            width,
+            height,
+            stride,
+        }
+    }
+
+    /// What a viewer shows for `stored` with the EXIF `orientation`, following the row and
+    /// column definitions of the EXIF specification.
+    fn apply_exif(stored: &RgbImage, orientation: u16) -> RgbImage {
+        let (w, h) = stored.dimensions();
+        let swapped = orientation >= 5;
+        let (width, height) = if swapped { (h, w) } else { (w, h) };
+        RgbImage::from_fn(width, height, |x, y| {
+            let (sx, sy) = match orientation {
+                1 => (x, y),
+                2 => (w - 1 - x, y),
+                3 => (w - 1 - x, h - 1 - y),
+                4 => (x, h - 1 - y),
+                5 => (y, x),
+                6 => (y, h - 1 - x),
+                7 => (w - 1 - y, h - 1 - x),
+                8 => (w - 1 - y, x),
+                _ => unreachable!(),
+            };
+            *stored.get_pixel(sx, sy)
+        })
+    }
+
+    #[test]
+    fn exif_orientation_matches_undo_transform() {
+        let stored = RgbImage::from_fn(3, 2, |x, y| image::Rgb([x as u8, y as u8, 0]));
+        for transform in [
+            Transform::Normal,
+            Transform::_90,
+            Transform::_180,
+            Transform::_270,
+            Transform::Flipped,
+            Transform::Flipped90,
+            Transform::Flipped180,
+            Transform::Flipped270,
+        ] {
+            let orientation = exif_orientation(Some(transform)).unwrap_or(1);
+            assert_eq!(
+                apply_exif(&stored, orientation),
+                undo_transform(stored.clone(), Some(transform)),
+                "{:?}",
+                transform
+            );
+        }
+    }
+
+    #[test]
+    fn pool_size_of_an_8k_frame() {
+        let size = pool_size(&frame_format(7680, 4320, 7680 * 4)).unwrap();
+        assert_eq!(size, 7680 * 4320 * 4);
+    }
+
+    #[test]
+    fn pool_size_rejects_empty_frames() {
+        assert!(pool_size(&frame_format(0, 1080, 0)).is_err());
+        assert!(pool_size(&frame_format(1920, 0, 1920 * 4)).is_err());
+    }
+
+    #[test]
+    fn pool_size_rejects_short_strides() {
+        assert!(pool_size(&frame_format(1920, 1080, 1920 * 4 - 1)).is_err());
+        assert!(pool_size(&frame_format(1920, 1080, 1920 * 4 + 64)).is_ok());
+    }
+
+    #[test]
+    fn pool_size_rejects_pools_past_i32_max() {
+        // Exactly 2^31 bytes, one more than a wl_shm pool can hold.
+        assert!(pool_size(&frame_format(16384, 32768, 16384 * 4)).is_err());
+        assert_eq!(
+            pool_size(&frame_format(1, i32::MAX as u32 / 4, 4)).unwrap(),
+            i32::MAX / 4 * 4
+        );
+        // A stride that would overflow u32 maths if multiplied naively.
+        assert!(pool_size(&frame_format(u32::MAX / 4, 2, u32::MAX - 3)).is_err());
+    }
+}
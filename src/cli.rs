@@ -0,0 +1,156 @@
+use clap::{arg, ArgMatches, Command, ValueSource};
+
+pub fn set_flags() -> Command<'static> {
+    Command::new("wayshot")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Screenshot tool for compositors implementing zwlr_screencopy_v1")
+        .after_help(
+            "Options can also be set through the WAYSHOT_* environment variables shown above; \
+             flags are enabled by any value except 0, false, no or off. Options given on the \
+             command line take precedence.",
+        )
+        .arg(
+            arg!(-o --output <OUTPUT> "Capture the output with this name, e.g. DP-1").env("WAYSHOT_OUTPUT")
+                .required(false),
+        )
+        .arg(
+            arg!(-g --geometry <GEOMETRY> "Capture a region given as \"x,y WxH\", as printed by slurp").env("WAYSHOT_GEOMETRY")
+                .required(false),
+        )
+        .arg(
+            arg!(-f --format <FORMAT> "Image format; defaults to the file extension, PNG for files and JPEG for stdout").env("WAYSHOT_FORMAT")
+                .required(false)
+                .possible_values(["jpeg", "jpg", "png", "bmp", "tif", "tiff"]),
+        )
+        .arg(
+            arg!(--dpi <DPI> "Pixel density to record in the image instead of the output's").env("WAYSHOT_DPI")
+                .required(false),
+        )
+        .arg(
+            arg!(--"output-file" <FILE> "Where to write the screenshot, - for stdout").env("WAYSHOT_OUTPUT_FILE")
+                .required(false)
+                .allow_invalid_utf8(true),
+        )
+        .arg(
+            arg!([FILE] "Where to write the screenshot, - for stdout (the default)")
+                .allow_invalid_utf8(true),
+        )
+        .arg(
+            arg!(-a --"all-outputs" "Capture every output, composited into one image").env("WAYSHOT_ALL_OUTPUTS"),
+        )
+        .arg(
+            arg!(--"filter-scale" <FILTER> "Filter for scaling lower-density outputs up when combining outputs").env("WAYSHOT_FILTER_SCALE")
+                .required(false)
+                .possible_values(["nearest", "bilinear", "lanczos3", "catmull-rom"]),
+        )
+        .arg(arg!(--"orientation-metadata" "Store a rotated output's orientation as EXIF metadata instead of rotating the pixels; JPEG and PNG only").env("WAYSHOT_ORIENTATION_METADATA"))
+        .arg(arg!(--"wake-output" "Turn powered-off outputs on for the capture, then off again").env("WAYSHOT_WAKE_OUTPUT"))
+        .arg(arg!(--"wait-vsync" "Capture the next frame the compositor draws; waits until something on screen changes").env("WAYSHOT_WAIT_VSYNC"))
+        .arg(arg!(-c --cursor "Include the pointer, if the compositor supports drawing it into captures").env("WAYSHOT_CURSOR"))
+        .arg(arg!(-l --"list-outputs" "List the connected outputs and exit"))
+}
+
+/// Which of the mutually exclusive options `ids` takes effect, if any.
+///
+/// One given on the command line wins over one from the environment, so a launcher's
+/// `WAYSHOT_OUTPUT` does not get in the way of `wayshot -g ...`. Two from the same source
+/// are an error.
+pub fn exclusive<'a>(matches: &ArgMatches, ids: &[&'a str]) -> Result<Option<&'a str>, String> {
+    let present: Vec<_> = ids
+        .iter()
+        .copied()
+        .filter(|id| matches.is_present(id))
+        .collect();
+    let from_command_line: Vec<_> = present
+        .iter()
+        .copied()
+        .filter(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        .collect();
+
+    match (from_command_line.as_slice(), present.as_slice()) {
+        ([id], _) => Ok(Some(id)),
+        ([first, second, ..], _) => Err(format!(
+            "{} cannot be used with {}",
+            option_name(first),
+            option_name(second)
+        )),
+        ([], []) => Ok(None),
+        ([], [id]) => Ok(Some(id)),
+        ([], [first, second, ..]) => Err(format!(
+            "{} and {} cannot both be set",
+            env_name(first),
+            env_name(second)
+        )),
+    }
+}
+
+fn option_name(id: &str) -> String {
+    if id.chars().all(|c| c.is_ascii_uppercase()) {
+        id.to_string()
+    } else {
+        format!("--{}", id)
+    }
+}
+
+fn env_name(id: &str) -> String {
+    format!("WAYSHOT_{}", id.to_uppercase().replace('-', "_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGETS: &[&str] = &["output", "geometry", "all-outputs"];
+
+    fn exclusive_from(args: &[&str], ids: &[&'static str]) -> Result<Option<&'static str>, String> {
+        let matches = set_flags().get_matches_from(args);
+        exclusive(&matches, ids)
+    }
+
+    #[test]
+    fn command_line_options_conflict() {
+        assert_eq!(
+            exclusive_from(&["wayshot", "-o", "DP-1", "-a"], TARGETS),
+            Err("--output cannot be used with --all-outputs".to_string())
+        );
+        assert_eq!(
+            exclusive_from(
+                &["wayshot", "--output-file", "a.png", "b.png"],
+                &["FILE", "output-file"]
+            ),
+            Err("FILE cannot be used with --output-file".to_string())
+        );
+        assert_eq!(
+            exclusive_from(&["wayshot", "-a"], TARGETS),
+            Ok(Some("all-outputs"))
+        );
+        assert_eq!(exclusive_from(&["wayshot"], TARGETS), Ok(None));
+    }
+
+    // All environment handling lives in one test, as the variables are process-wide.
+    #[test]
+    fn command_line_wins_over_environment() {
+        std::env::set_var("WAYSHOT_OUTPUT", "DP-1");
+        assert_eq!(exclusive_from(&["wayshot"], TARGETS), Ok(Some("output")));
+        assert_eq!(
+            exclusive_from(&["wayshot", "-g", "0,0 1x1"], TARGETS),
+            Ok(Some("geometry"))
+        );
+
+        std::env::set_var("WAYSHOT_ALL_OUTPUTS", "0");
+        assert_eq!(exclusive_from(&["wayshot"], TARGETS), Ok(Some("output")));
+        std::env::set_var("WAYSHOT_ALL_OUTPUTS", "1");
+        assert_eq!(
+            exclusive_from(&["wayshot"], TARGETS),
+            Err("WAYSHOT_OUTPUT and WAYSHOT_ALL_OUTPUTS cannot both be set".to_string())
+        );
+        assert_eq!(
+            exclusive_from(&["wayshot", "-o", "HDMI-A-1"], TARGETS),
+            Ok(Some("output"))
+        );
+
+        std::env::remove_var("WAYSHOT_OUTPUT");
+        std::env::remove_var("WAYSHOT_ALL_OUTPUTS");
+    }
+}
@@ -0,0 +1,163 @@
+/// Compositors we know how to give targeted advice for.
+#[derive(Debug, PartialEq)]
+pub enum Compositor {
+    Gnome,
+    Kde,
+    Weston,
+    /// A wlroots-based compositor, which would normally offer wlr-screencopy. Holds the
+    /// desktop name when it is known.
+    Wlroots(Option<String>),
+    Other(String),
+    Unknown,
+}
+
+/// Desktop names of compositors that implement wlr-screencopy.
+const WLROOTS_DESKTOPS: &[&str] = &["sway", "river", "hyprland", "wayfire", "labwc", "dwl"];
+
+/// Identify the running compositor from the globals it advertises, falling back to the
+/// `desktop` name (the value of `XDG_CURRENT_DESKTOP`) when none of the globals are
+/// distinctive.
+///
+/// Only globals unique to one compositor count: wlroots compositors also implement
+/// `org_kde_kwin_server_decoration_manager`, and phoc implements `gtk_shell1`.
+pub fn identify(globals: &[(u32, String, u32)], desktop: Option<&str>) -> Compositor {
+    let advertises = |prefix: &str| globals.iter().any(|(_, name, _)| name.starts_with(prefix));
+    let desktop = desktop.filter(|desktop| !desktop.is_empty());
+    // XDG_CURRENT_DESKTOP is a colon-separated list such as "ubuntu:GNOME".
+    let desktop_is = |wanted: &[&str]| {
+        desktop.is_some_and(|desktop| {
+            desktop
+                .split(':')
+                .any(|name| wanted.contains(&name.to_lowercase().as_str()))
+        })
+    };
+
+    if advertises("org_kde_plasma_shell") || advertises("org_kde_kwin_outputmanagement") {
+        return Compositor::Kde;
+    }
+    if advertises("weston_") {
+        return Compositor::Weston;
+    }
+    if advertises("zwlr_") || desktop_is(WLROOTS_DESKTOPS) {
+        return Compositor::Wlroots(desktop.map(str::to_string));
+    }
+    if advertises("gtk_shell") {
+        return Compositor::Gnome;
+    }
+
+    match desktop {
+        _ if desktop_is(&["gnome"]) => Compositor::Gnome,
+        _ if desktop_is(&["kde"]) => Compositor::Kde,
+        Some(desktop) => Compositor::Other(desktop.to_string()),
+        None => Compositor::Unknown,
+    }
+}
+
+/// Explain why `zwlr_screencopy_manager_v1` is missing on the given compositor.
+pub fn missing_screencopy_hint(compositor: &Compositor) -> String {
+    match compositor {
+        Compositor::Gnome => "GNOME (mutter) does not implement wlr-screencopy; \
+             take screenshots through the xdg-desktop-portal or gnome-screenshot instead."
+            .to_string(),
+        Compositor::Kde => "KDE Plasma (kwin) does not implement wlr-screencopy; \
+             take screenshots through the xdg-desktop-portal or spectacle instead."
+            .to_string(),
+        Compositor::Weston => "weston only exposes its own privileged screenshooter protocol; \
+             wlr-screencopy is not available."
+            .to_string(),
+        Compositor::Wlroots(desktop) => format!(
+            "{} normally supports wlr-screencopy but does not advertise it; the compositor may \
+             be too old, or a sandbox or security context may be hiding privileged protocols \
+             from wayshot.",
+            desktop.as_deref().unwrap_or("The compositor")
+        ),
+        Compositor::Other(desktop) => format!(
+            "{} does not advertise wlr-screencopy; wayshot requires a compositor implementing \
+             it, such as sway, river or Hyprland.",
+            desktop
+        ),
+        Compositor::Unknown => {
+            "The compositor does not advertise wlr-screencopy; wayshot requires \
+             a compositor implementing it, such as sway, river or Hyprland."
+                .to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn globals(names: &[&str]) -> Vec<(u32, String, u32)> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| (id as u32 + 1, name.to_string(), 1))
+            .collect()
+    }
+
+    #[test]
+    fn kwin_is_identified_by_its_own_globals() {
+        let kwin = globals(&["wl_compositor", "org_kde_plasma_shell"]);
+        assert_eq!(identify(&kwin, None), Compositor::Kde);
+    }
+
+    #[test]
+    fn kde_decoration_global_does_not_mean_kwin() {
+        let sway = globals(&[
+            "wl_compositor",
+            "org_kde_kwin_server_decoration_manager",
+            "zwlr_layer_shell_v1",
+        ]);
+        assert_eq!(
+            identify(&sway, Some("sway")),
+            Compositor::Wlroots(Some("sway".to_string()))
+        );
+        assert_eq!(identify(&sway, None), Compositor::Wlroots(None));
+    }
+
+    #[test]
+    fn phoc_is_not_gnome() {
+        let phoc = globals(&["gtk_shell1", "zwlr_layer_shell_v1"]);
+        assert_eq!(
+            identify(&phoc, Some("Phosh:GNOME")),
+            Compositor::Wlroots(Some("Phosh:GNOME".to_string()))
+        );
+    }
+
+    #[test]
+    fn mutter_is_gnome() {
+        let mutter = globals(&["wl_compositor", "gtk_shell1"]);
+        assert_eq!(identify(&mutter, None), Compositor::Gnome);
+    }
+
+    #[test]
+    fn weston_is_identified_by_its_globals() {
+        let weston = globals(&["weston_capture_v1"]);
+        assert_eq!(identify(&weston, None), Compositor::Weston);
+    }
+
+    #[test]
+    fn desktop_name_is_the_fallback() {
+        let plain = globals(&["wl_compositor"]);
+        assert_eq!(identify(&plain, Some("ubuntu:GNOME")), Compositor::Gnome);
+        assert_eq!(identify(&plain, Some("KDE")), Compositor::Kde);
+        assert_eq!(
+            identify(&plain, Some("Hyprland")),
+            Compositor::Wlroots(Some("Hyprland".to_string()))
+        );
+        assert_eq!(
+            identify(&plain, Some("niri")),
+            Compositor::Other("niri".to_string())
+        );
+        assert_eq!(identify(&plain, Some("")), Compositor::Unknown);
+        assert_eq!(identify(&plain, None), Compositor::Unknown);
+    }
+
+    #[test]
+    fn wlroots_hint_does_not_ask_for_a_wlroots_compositor() {
+        let hint = missing_screencopy_hint(&Compositor::Wlroots(Some("sway".to_string())));
+        assert!(hint.starts_with("sway normally supports wlr-screencopy"));
+        assert!(!hint.contains("requires"));
+    }
+}
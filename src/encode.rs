@@ -0,0 +1,252 @@
+use std::error::Error;
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::jpeg::{JpegEncoder, PixelDensity, PixelDensityUnit};
+use image::codecs::tiff::TiffEncoder;
+use image::{ColorType, RgbImage};
+
+const METRES_PER_INCH: f64 = 0.0254;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Bmp,
+    Tiff,
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
+            "png" => Ok(ImageFormat::Png),
+            "bmp" => Ok(ImageFormat::Bmp),
+            "tif" | "tiff" => Ok(ImageFormat::Tiff),
+            _ => Err(format!("Unsupported image format {:?}", s)),
+        }
+    }
+}
+
+impl ImageFormat {
+    /// The format implied by `path`'s extension, if it has one.
+    pub fn from_path(path: &Path) -> Option<Result<ImageFormat, String>> {
+        path.extension()
+            .map(|extension| extension.to_string_lossy().parse())
+    }
+}
+
+impl ImageFormat {
+    /// Whether [`encode`] can record an EXIF orientation in this format.
+    pub fn supports_orientation(self) -> bool {
+        matches!(self, ImageFormat::Jpeg | ImageFormat::Png)
+    }
+}
+
+/// Encode `image` in `format`. `dpi` is recorded where the format has a place for it, and
+/// `orientation` (an EXIF orientation value) where [`ImageFormat::supports_orientation`].
+pub fn encode(
+    image: &RgbImage,
+    format: ImageFormat,
+    dpi: Option<(u16, u16)>,
+    orientation: Option<u16>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut encoded = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let mut encoder = JpegEncoder::new(&mut encoded);
+            if let Some(density) = dpi {
+                encoder.set_pixel_density(PixelDensity {
+                    density,
+                    unit: PixelDensityUnit::Inches,
+                });
+            }
+            encoder.encode_image(image)?;
+            if let Some(orientation) = orientation {
+                insert_jpeg_exif(&mut encoded, orientation);
+            }
+        }
+        ImageFormat::Png => encode_png(&mut encoded, image, dpi, orientation)?,
+        // image's BMP and TIFF encoders have no way to set the resolution, so the density is
+        // dropped for them.
+        ImageFormat::Bmp => BmpEncoder::new(&mut encoded).encode(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ColorType::Rgb8,
+        )?,
+        ImageFormat::Tiff => TiffEncoder::new(Cursor::new(&mut encoded)).encode(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ColorType::Rgb8,
+        )?,
+    }
+    Ok(encoded)
+}
+
+/// Encode through png directly, as image's PNG encoder has no way to write a pHYs chunk.
+fn encode_png(
+    encoded: &mut Vec<u8>,
+    image: &RgbImage,
+    dpi: Option<(u16, u16)>,
+    orientation: Option<u16>,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(encoded, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+
+    if let Some((horizontal, vertical)) = dpi {
+        // pHYs holds pixels per metre for each axis, followed by the unit (1 = metre).
+        let per_metre = |dpi: u16| (dpi as f64 / METRES_PER_INCH).round() as u32;
+        let mut phys = Vec::with_capacity(9);
+        phys.extend_from_slice(&per_metre(horizontal).to_be_bytes());
+        phys.extend_from_slice(&per_metre(vertical).to_be_bytes());
+        phys.push(1);
+        writer.write_chunk(png::chunk::pHYs, &phys)?;
+    }
+    if let Some(orientation) = orientation {
+        writer.write_chunk(png::chunk::ChunkType(*b"eXIf"), &exif(orientation))?;
+    }
+
+    writer.write_image_data(image.as_raw())?;
+    writer.finish()
+}
+
+/// A minimal big-endian EXIF (TIFF) structure holding only the orientation tag.
+fn exif(orientation: u16) -> Vec<u8> {
+    const ORIENTATION_TAG: u16 = 0x0112;
+    const SHORT: u16 = 3;
+
+    let mut exif = Vec::with_capacity(26);
+    exif.extend_from_slice(b"MM\0\x2a");
+    // The first IFD follows the 8 byte header directly.
+    exif.extend_from_slice(&8u32.to_be_bytes());
+    exif.extend_from_slice(&1u16.to_be_bytes());
+    exif.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+    exif.extend_from_slice(&SHORT.to_be_bytes());
+    exif.extend_from_slice(&1u32.to_be_bytes());
+    // A single SHORT is stored left-aligned in the 4 byte value field.
+    exif.extend_from_slice(&orientation.to_be_bytes());
+    exif.extend_from_slice(&[0, 0]);
+    // No further IFDs.
+    exif.extend_from_slice(&0u32.to_be_bytes());
+    exif
+}
+
+/// Insert an APP1 EXIF segment after the JFIF APP0 segment, which has to stay first.
+fn insert_jpeg_exif(encoded: &mut Vec<u8>, orientation: u16) {
+    let mut segment = b"Exif\0\0".to_vec();
+    segment.extend_from_slice(&exif(orientation));
+    let mut app1 = vec![0xff, 0xe1];
+    app1.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+    app1.extend_from_slice(&segment);
+
+    // Skip SOI, and APP0 if present.
+    let mut offset = 2;
+    if encoded.get(2..4) == Some(&[0xff, 0xe0]) {
+        offset += 2 + u16::from_be_bytes([encoded[4], encoded[5]]) as usize;
+    }
+    encoded.splice(offset..offset, app1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_from_extension_ignores_case() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("shot.JPG")),
+            Some(Ok(ImageFormat::Jpeg))
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("shot.png")),
+            Some(Ok(ImageFormat::Png))
+        );
+    }
+
+    #[test]
+    fn format_from_bmp_and_tiff_extensions() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("shot.bmp")),
+            Some(Ok(ImageFormat::Bmp))
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("shot.TIF")),
+            Some(Ok(ImageFormat::Tiff))
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("shot.tiff")),
+            Some(Ok(ImageFormat::Tiff))
+        );
+    }
+
+    #[test]
+    fn format_from_path_without_extension() {
+        assert_eq!(ImageFormat::from_path(Path::new("shot")), None);
+    }
+
+    #[test]
+    fn format_from_unsupported_extension() {
+        assert!(matches!(
+            ImageFormat::from_path(Path::new("shot.webp")),
+            Some(Err(_))
+        ));
+    }
+
+    fn test_image() -> RgbImage {
+        RgbImage::from_fn(5, 3, |x, y| image::Rgb([x as u8 * 50, y as u8 * 100, 7]))
+    }
+
+    #[test]
+    fn bmp_round_trips() {
+        let image = test_image();
+        let encoded = encode(&image, ImageFormat::Bmp, None, None).unwrap();
+        assert_eq!(&encoded[..2], b"BM");
+        let decoded = image::load_from_memory_with_format(&encoded, image::ImageFormat::Bmp)
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn tiff_round_trips() {
+        let image = test_image();
+        let encoded = encode(&image, ImageFormat::Tiff, None, None).unwrap();
+        let decoded = image::load_from_memory_with_format(&encoded, image::ImageFormat::Tiff)
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn jpeg_records_orientation_after_jfif() {
+        let image = test_image();
+        let encoded = encode(&image, ImageFormat::Jpeg, Some((96, 96)), Some(6)).unwrap();
+        let app0_end = 4 + u16::from_be_bytes([encoded[4], encoded[5]]) as usize;
+        assert_eq!(&encoded[2..4], [0xff, 0xe0]);
+        assert_eq!(&encoded[app0_end..app0_end + 2], [0xff, 0xe1]);
+        assert_eq!(&encoded[app0_end + 4..app0_end + 10], b"Exif\0\0");
+        assert_eq!(&encoded[app0_end + 10..app0_end + 10 + 26], &exif(6)[..]);
+        let decoded = image::load_from_memory_with_format(&encoded, image::ImageFormat::Jpeg);
+        assert_eq!(decoded.unwrap().width(), image.width());
+    }
+
+    #[test]
+    fn png_records_orientation_before_the_image_data() {
+        let image = test_image();
+        let encoded = encode(&image, ImageFormat::Png, None, Some(8)).unwrap();
+        let exif_chunk = encoded.windows(4).position(|w| w == b"eXIf").unwrap();
+        let idat = encoded.windows(4).position(|w| w == b"IDAT").unwrap();
+        assert!(exif_chunk < idat);
+        assert_eq!(&encoded[exif_chunk + 4..exif_chunk + 4 + 26], &exif(8)[..]);
+        let decoded = image::load_from_memory_with_format(&encoded, image::ImageFormat::Png);
+        assert_eq!(decoded.unwrap().to_rgb8(), image);
+    }
+}
@@ -0,0 +1,165 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// How many temporary names to try before giving up, should earlier runs have left files
+/// behind under the first ones.
+const TEMP_ATTEMPTS: u32 = 16;
+
+/// Write `data` to `path` through a temporary file in the same directory that is renamed
+/// into place once complete, so a crash or full disk never leaves a truncated image behind.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let (temp_path, mut file) = create_temp_file(path)?;
+    // From here on the temporary file is ours, so it is removed again on failure.
+    let result = file
+        .write_all(data)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Create a fresh temporary file next to `path`. A name that already exists belongs to
+/// someone else (or a crashed earlier run with the same PID), so it is skipped rather than
+/// reused or removed.
+fn create_temp_file(path: &Path) -> io::Result<(PathBuf, File)> {
+    let mut attempt = 0;
+    loop {
+        let temp_path = temp_path_for(path, attempt)?;
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt + 1 < TEMP_ATTEMPTS => {
+                attempt += 1
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn temp_path_for(path: &Path, attempt: u32) -> io::Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".wayshot-{}", std::process::id()));
+    if attempt > 0 {
+        temp_name.push(format!("-{}", attempt));
+    }
+    temp_name.push(".tmp");
+    Ok(path.with_file_name(temp_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temporary directory, removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            let path =
+                std::env::temp_dir().join(format!("wayshot-test-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir(&path).unwrap();
+            TestDir(path)
+        }
+
+        fn entries(&self) -> Vec<OsString> {
+            let mut entries: Vec<_> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            entries.sort();
+            entries
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn replaces_an_existing_file() {
+        let dir = TestDir::new("replace");
+        let path = dir.0.join("shot.png");
+        fs::write(&path, b"old contents that are longer").unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(dir.entries(), ["shot.png"]);
+    }
+
+    #[test]
+    fn removes_the_temporary_file_when_the_rename_fails() {
+        let dir = TestDir::new("rename");
+        let path = dir.0.join("shot.png");
+        fs::create_dir(&path).unwrap();
+
+        assert!(write_atomic(&path, b"data").is_err());
+        assert!(path.is_dir());
+        assert_eq!(dir.entries(), ["shot.png"]);
+    }
+
+    #[test]
+    fn temp_path_needs_a_file_name() {
+        assert!(temp_path_for(Path::new("/"), 0).is_err());
+        assert!(temp_path_for(Path::new("shots/.."), 0).is_err());
+        assert_eq!(
+            temp_path_for(Path::new("shots/a.png"), 0).unwrap(),
+            Path::new(&format!("shots/.a.png.wayshot-{}.tmp", std::process::id()))
+        );
+        assert_eq!(
+            temp_path_for(Path::new("shots/a.png"), 2).unwrap(),
+            Path::new(&format!(
+                "shots/.a.png.wayshot-{}-2.tmp",
+                std::process::id()
+            ))
+        );
+    }
+
+    #[test]
+    fn leaves_stale_temporary_files_alone() {
+        let dir = TestDir::new("stale");
+        let path = dir.0.join("shot.png");
+        let stale = temp_path_for(&path, 0).unwrap();
+        fs::write(&stale, b"left behind").unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read(&stale).unwrap(), b"left behind");
+        assert_eq!(dir.entries().len(), 2);
+    }
+
+    #[test]
+    fn does_not_remove_files_it_did_not_create() {
+        let dir = TestDir::new("foreign");
+        let path = dir.0.join("shot.png");
+        let foreign: Vec<_> = (0..TEMP_ATTEMPTS)
+            .map(|attempt| temp_path_for(&path, attempt).unwrap())
+            .collect();
+        for temp_path in &foreign {
+            fs::write(temp_path, b"someone else's").unwrap();
+        }
+
+        let error = write_atomic(&path, b"new").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert!(foreign.iter().all(|temp_path| temp_path.exists()));
+        assert!(!path.exists());
+    }
+}
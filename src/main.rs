@@ -1,35 +1,351 @@
-use sctk::reexports::client::protocol::wl_shm::{Format, WlShm};
-use sctk::reexports::client::{protocol::wl_output::WlOutput, Display, GlobalManager};
-use sctk::reexports::protocols::wlr::unstable::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1;
-use smithay_client_toolkit as sctk;
-use std::fs::File;
-use std::os::unix::io::AsRawFd;
+use std::env;
+use std::io::{stdout, Write};
+use std::path::Path;
+use std::process::exit;
+
+use encode::ImageFormat;
+use region::Region;
+use wayland_client::protocol::wl_shm::WlShm;
+use wayland_client::{Display, EventQueue, GlobalManager};
+use wayland_protocols::unstable::xdg_output::v1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1;
+use wayland_protocols::wlr::unstable::output_power_management::v1::client::zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1;
+use wayland_protocols::wlr::unstable::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1;
+
+mod capture;
+mod cli;
+mod diagnostics;
+mod encode;
+mod file;
+mod output;
+mod region;
+mod shm;
+mod stitch;
+
+/// Highest protocol versions wayshot knows how to drive; the compositor may offer less.
+const SCREENCOPY_VERSION: u32 = 3;
+const OUTPUT_VERSION: u32 = 4;
 
 fn main() {
-    let display = Display::connect_to_env().unwrap();
+    let args = cli::set_flags().get_matches();
+    let exclusive = |ids| {
+        cli::exclusive(&args, ids).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1);
+        })
+    };
+    let selection = exclusive(&["output", "geometry", "all-outputs"]);
+    let all_outputs = selection == Some("all-outputs");
+
+    let file = exclusive(&["FILE", "output-file"])
+        .and_then(|id| args.value_of_os(id))
+        .map(Path::new)
+        .filter(|path| *path != Path::new("-"));
+
+    let format = match (args.value_of("format"), file) {
+        // clap has already restricted the value to the formats ImageFormat knows.
+        (Some(format), _) => format.parse().unwrap(),
+        (None, Some(path)) => match ImageFormat::from_path(path) {
+            Some(Ok(format)) => format,
+            Some(Err(e)) => {
+                eprintln!("{}; pass --format to choose one", e);
+                exit(1);
+            }
+            None => ImageFormat::Png,
+        },
+        (None, None) => ImageFormat::Jpeg,
+    };
+
+    let dpi_override = args.value_of("dpi").map(|dpi| match dpi.parse::<u16>() {
+        Ok(dpi) if dpi > 0 => (dpi, dpi),
+        _ => {
+            eprintln!(
+                "Invalid --dpi {:?}, expected a whole number from 1 to 65535",
+                dpi
+            );
+            exit(1);
+        }
+    });
+
+    if args.is_present("orientation-metadata") && !format.supports_orientation() {
+        eprintln!("--orientation-metadata needs JPEG or PNG output");
+        exit(1);
+    }
+
+    let display = match Display::connect_to_env() {
+        Ok(display) => display,
+        Err(e) => {
+            let wayland_display = match env::var_os("WAYLAND_DISPLAY") {
+                Some(name) => format!("WAYLAND_DISPLAY={}", name.to_string_lossy()),
+                None => "WAYLAND_DISPLAY is not set".to_string(),
+            };
+            eprintln!(
+                "No Wayland compositor is reachable ({}): {}",
+                wayland_display, e
+            );
+            exit(1);
+        }
+    };
     let mut event_queue = display.create_event_queue();
     let attached_display = display.attach(event_queue.token());
     let registry = attached_display.get_registry();
     let globals = GlobalManager::new(&attached_display);
 
-    event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+    let roundtrip = |event_queue: &mut EventQueue| {
+        if let Err(e) = event_queue.sync_roundtrip(&mut (), |_, _, _| {}) {
+            eprintln!("Lost the connection to the compositor: {}", e);
+            exit(1);
+        }
+    };
+
+    roundtrip(&mut event_queue);
+
+    let outputs = output::bind_all(&registry, &globals, OUTPUT_VERSION);
+    let shm = match globals.instantiate_exact::<WlShm>(1) {
+        Ok(shm) => shm,
+        Err(_) => {
+            eprintln!("Compositor does not advertise wl_shm, which wayshot needs for captures");
+            exit(1);
+        }
+    };
 
-    let screencopy_manager = globals
-        .instantiate_exact::<ZwlrScreencopyManagerV1>(1)
-        .expect("Unable to init screencopy_manager");
+    roundtrip(&mut event_queue);
 
-    let output = registry.bind::<WlOutput>(1, 1);
-    let shm = registry.bind::<WlShm>(1, 1);
+    if let Ok(xdg_output_manager) = globals.instantiate_range::<ZxdgOutputManagerV1>(1, 3) {
+        output::track_xdg_outputs(&mut event_queue, &xdg_output_manager, &outputs);
+        xdg_output_manager.destroy();
+    }
 
-    let fd: File = tempfile::tempfile().unwrap();
-    let shm_pool = shm.create_pool(fd.as_raw_fd(), 2147483647);
+    if args.is_present("list-outputs") {
+        for output in &outputs {
+            println!("{}", output.info.borrow());
+        }
+        return;
+    }
 
-    let frame = screencopy_manager.capture_output(0, &output);
-    let buffer = shm_pool.create_buffer(0, 1920, 1080, 5760, Format::Argb8888);
-    frame.copy(&buffer);
+    let geometry = args
+        .value_of("geometry")
+        .filter(|_| selection == Some("geometry"))
+        .map(|geometry| {
+            geometry.parse::<Region>().unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                exit(1);
+            })
+        });
 
-    frame.destroy();
-    buffer.destroy();
-    shm_pool.destroy();
+    // Each target is an output and, for --geometry, the part of the geometry it shows.
+    let targets: Vec<(&output::Output, Option<Region>)> = match (
+        geometry,
+        args.value_of("output")
+            .filter(|_| selection == Some("output")),
+    ) {
+        _ if all_outputs => outputs.iter().map(|output| (output, None)).collect(),
+        (Some(geometry), _) => {
+            let targets: Vec<_> = outputs
+                .iter()
+                .filter_map(|output| {
+                    let output_region = output.info.borrow().logical_region();
+                    let area = output_region.intersection(&geometry)?;
+                    Some((output, Some(area)))
+                })
+                .collect();
+            let covered = targets
+                .iter()
+                .filter_map(|(_, area)| *area)
+                .reduce(|covered, area| covered.union(&area));
+            if !covered.is_some_and(|covered| covered.contains(&geometry)) {
+                eprintln!("Geometry {} extends beyond the outputs", geometry);
+                exit(1);
+            }
+            targets
+        }
+        (None, Some(name)) => match outputs
+            .iter()
+            .find(|output| output.info.borrow().name.as_deref() == Some(name))
+        {
+            Some(output) => vec![(output, None)],
+            None => {
+                eprintln!("No output named {}; use --list-outputs to see them", name);
+                exit(1);
+            }
+        },
+        (None, None) => outputs
+            .iter()
+            .take(1)
+            .map(|output| (output, None))
+            .collect(),
+    };
+    if targets.is_empty() {
+        eprintln!("Compositor does not advertise any wl_output");
+        exit(1);
+    }
+
+    let screencopy_manager =
+        match globals.instantiate_range::<ZwlrScreencopyManagerV1>(1, SCREENCOPY_VERSION) {
+            Ok(manager) => manager,
+            Err(_) => {
+                let desktop = env::var("XDG_CURRENT_DESKTOP").ok();
+                let compositor = diagnostics::identify(&globals.list(), desktop.as_deref());
+                eprintln!("Unable to init screencopy_manager");
+                eprintln!("{}", diagnostics::missing_screencopy_hint(&compositor));
+                exit(1);
+            }
+        };
+
+    if args.is_present("wait-vsync")
+        && screencopy_manager.as_ref().version() < capture::COPY_WITH_DAMAGE_SINCE
+    {
+        eprintln!(
+            "Warning: the compositor's screencopy is too old to wait for the next frame, \
+             capturing the current one"
+        );
+    }
+
+    // A powered-off output is still advertised, but most compositors hand back a black or
+    // stale frame for it.
+    let power_manager = globals
+        .instantiate_exact::<ZwlrOutputPowerManagerV1>(1)
+        .ok();
+    let mut woken = Vec::new();
+    if let Some(power_manager) = &power_manager {
+        for (target, _) in &targets {
+            if !output::is_powered_off(&mut event_queue, power_manager, &target.wl_output) {
+                continue;
+            }
+            let name = target.info.borrow().name.clone();
+            let name = name.as_deref().unwrap_or("<unnamed>");
+            if !args.is_present("wake-output") {
+                eprintln!(
+                    "Warning: output {} is powered off, the screenshot will likely be black; \
+                     pass --wake-output to turn it on",
+                    name
+                );
+                continue;
+            }
+            match output::wake(&mut event_queue, power_manager, &target.wl_output) {
+                Some(output_power) => woken.push(output_power),
+                None => eprintln!(
+                    "Warning: unable to turn output {} on, the screenshot will likely be black",
+                    name
+                ),
+            }
+        }
+    }
+    // Outputs woken for the capture go back to sleep as soon as their frames have been copied.
+    let release_outputs = |woken: Vec<_>| {
+        woken.into_iter().for_each(output::put_to_sleep);
+        if let Some(power_manager) = &power_manager {
+            power_manager.destroy();
+        }
+    };
+
+    // Outputs being composited have to be upright, so the rotation can only be left to the
+    // viewer when a single output is captured.
+    let keep_orientation = args.is_present("orientation-metadata") && targets.len() == 1;
+    if args.is_present("orientation-metadata") && !keep_orientation {
+        eprintln!("Warning: --orientation-metadata does not apply when combining outputs");
+    }
+
+    let areas: Vec<_> = targets
+        .iter()
+        .map(|(target, area)| area.unwrap_or_else(|| target.info.borrow().logical_region()))
+        .collect();
+    let requests: Vec<_> = targets
+        .iter()
+        .zip(&areas)
+        .map(|((target, geometry_part), area)| {
+            let output_region = target.info.borrow().logical_region();
+            capture::CaptureRequest {
+                output: &target.wl_output,
+                // capture_output_region takes coordinates relative to the output.
+                region: geometry_part.map(|_| Region {
+                    x: area.x - output_region.x,
+                    y: area.y - output_region.y,
+                    ..*area
+                }),
+            }
+        })
+        .collect();
+    let frame_copies = match capture::capture_outputs(
+        &mut event_queue,
+        &screencopy_manager,
+        &shm,
+        &requests,
+        args.is_present("cursor"),
+        args.is_present("wait-vsync"),
+    ) {
+        Ok(frame_copies) => frame_copies,
+        Err(e) => {
+            release_outputs(woken);
+            screencopy_manager.destroy();
+            let _ = display.flush();
+            eprintln!("Unable to capture output: {}", e);
+            exit(1);
+        }
+    };
+
+    // Convert one frame at a time so each one's shared memory is released as soon as it has
+    // been read.
+    let mut captures = Vec::with_capacity(targets.len());
+    for ((frame_copy, (target, _)), area) in frame_copies.into_iter().zip(&targets).zip(areas) {
+        let transform = target.info.borrow().transform;
+        let image = frame_copy.to_rgb_image(transform.filter(|_| !keep_orientation));
+        captures.push((area, image));
+    }
+    // Give the protocol objects back before the potentially slow write: a stalled pipe should
+    // not keep the compositor's side of the capture alive.
+    release_outputs(woken);
     screencopy_manager.destroy();
+    let _ = display.flush();
+
+    let (image, dpi) = if captures.len() == 1 {
+        let (_, image) = captures.pop().unwrap();
+        let info = targets[0].0.info.borrow();
+        // dpi() describes the upright image; a stored sideways image has its axes swapped.
+        let dpi = info.dpi().map(|(horizontal, vertical)| {
+            if keep_orientation && info.is_rotated() {
+                (vertical, horizontal)
+            } else {
+                (horizontal, vertical)
+            }
+        });
+        (image, dpi_override.or(dpi))
+    } else {
+        // Outputs can differ in density, so the composite has no single DPI to record unless
+        // one is given.
+        // clap has already restricted the value to the filters stitch knows.
+        let filter = args
+            .value_of("filter-scale")
+            .map(|name| stitch::filter_from_name(name).unwrap());
+        match stitch::stitch(captures, filter) {
+            Ok(image) => (image, dpi_override),
+            Err(e) => {
+                eprintln!("Unable to combine outputs: {}", e);
+                exit(1);
+            }
+        }
+    };
+
+    let orientation = keep_orientation
+        .then(|| capture::exif_orientation(targets[0].0.info.borrow().transform))
+        .flatten();
+    let encoded = match encode::encode(&image, format, dpi, orientation) {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            eprintln!("Unable to encode image: {}", e);
+            exit(1);
+        }
+    };
+    drop(image);
+
+    let written = match file {
+        Some(path) => file::write_atomic(path, &encoded),
+        None => {
+            let mut stdout = stdout().lock();
+            stdout.write_all(&encoded).and_then(|_| stdout.flush())
+        }
+    };
+    if let Err(e) = written {
+        eprintln!("Unable to write image: {}", e);
+        exit(1);
+    }
 }
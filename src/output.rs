@@ -0,0 +1,342 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use wayland_client::protocol::wl_output::{self, WlOutput};
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::{EventQueue, GlobalManager, Main};
+use wayland_protocols::unstable::xdg_output::v1::client::{
+    zxdg_output_manager_v1::ZxdgOutputManagerV1, zxdg_output_v1,
+};
+use wayland_protocols::wlr::unstable::output_power_management::v1::client::{
+    zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1,
+    zwlr_output_power_v1::{self, ZwlrOutputPowerV1},
+};
+
+use crate::region::Region;
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// What the compositor has told us about a wl_output.
+#[derive(Debug, Default, Clone)]
+pub struct OutputInfo {
+    /// Connector name such as `DP-1`, from wl_output v4 or xdg-output.
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub scale: i32,
+    /// Position in the global compositor space, from xdg-output or else wl_output.geometry.
+    pub logical_x: i32,
+    pub logical_y: i32,
+    /// Size in the global compositor space as reported by xdg-output; zero if unknown.
+    pub logical_width: i32,
+    pub logical_height: i32,
+    /// Physical size in millimetres; zero when it does not make sense (projectors, virtual
+    /// outputs).
+    pub physical_width: i32,
+    pub physical_height: i32,
+    /// Size of the current mode in hardware pixels.
+    pub mode_width: i32,
+    pub mode_height: i32,
+    /// Subpixel layout of the panel, for consumers compensating for subpixel-rendered text.
+    pub subpixel: Option<wl_output::Subpixel>,
+    /// How the compositor rotates/flips content on this output.
+    pub transform: Option<wl_output::Transform>,
+}
+
+/// A bound wl_output together with what the compositor has told us about it.
+pub struct Output {
+    pub wl_output: Main<WlOutput>,
+    pub info: Rc<RefCell<OutputInfo>>,
+}
+
+/// Bind every advertised wl_output at up to `max_version`. Their `OutputInfo` is filled in by
+/// the next roundtrip.
+pub fn bind_all(
+    registry: &Main<WlRegistry>,
+    globals: &GlobalManager,
+    max_version: u32,
+) -> Vec<Output> {
+    globals
+        .list()
+        .into_iter()
+        .filter(|(_, interface, _)| interface == "wl_output")
+        .map(|(id, _, version)| {
+            let wl_output = registry.bind::<WlOutput>(version.min(max_version), id);
+            let info = track(&wl_output);
+            Output { wl_output, info }
+        })
+        .collect()
+}
+
+/// Fetch logical geometry (and names for outputs bound below wl_output v4, which has no name
+/// event of its own) through xdg-output.
+pub fn track_xdg_outputs(
+    event_queue: &mut EventQueue,
+    xdg_output_manager: &Main<ZxdgOutputManagerV1>,
+    outputs: &[Output],
+) {
+    let xdg_outputs: Vec<_> = outputs
+        .iter()
+        .map(|output| {
+            let xdg_output = xdg_output_manager.get_xdg_output(&output.wl_output);
+            xdg_output.quick_assign({
+                let info = output.info.clone();
+                move |_, event, _| {
+                    let mut info = info.borrow_mut();
+                    match event {
+                        zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                            info.logical_x = x;
+                            info.logical_y = y;
+                        }
+                        zxdg_output_v1::Event::LogicalSize { width, height } => {
+                            info.logical_width = width;
+                            info.logical_height = height;
+                        }
+                        zxdg_output_v1::Event::Name { name } => {
+                            info.name.get_or_insert(name);
+                        }
+                        _ => {}
+                    }
+                }
+            });
+            xdg_output
+        })
+        .collect();
+
+    if !xdg_outputs.is_empty() {
+        let _ = event_queue.sync_roundtrip(&mut (), |_, _, _| {});
+    }
+    for xdg_output in xdg_outputs {
+        xdg_output.destroy();
+    }
+}
+
+/// Record the output's events into an `OutputInfo`; it is filled in by the next roundtrip.
+fn track(output: &Main<WlOutput>) -> Rc<RefCell<OutputInfo>> {
+    let info = Rc::new(RefCell::new(OutputInfo {
+        scale: 1,
+        ..OutputInfo::default()
+    }));
+    output.quick_assign({
+        let info = info.clone();
+        move |_, event, _| {
+            let mut info = info.borrow_mut();
+            match event {
+                wl_output::Event::Geometry {
+                    x,
+                    y,
+                    physical_width,
+                    physical_height,
+                    subpixel,
+                    transform,
+                    ..
+                } => {
+                    info.subpixel = Some(subpixel);
+                    info.transform = Some(transform);
+                    info.logical_x = x;
+                    info.logical_y = y;
+                    info.physical_width = physical_width;
+                    info.physical_height = physical_height;
+                }
+                wl_output::Event::Mode {
+                    flags,
+                    width,
+                    height,
+                    ..
+                } if flags.contains(wl_output::Mode::Current) => {
+                    info.mode_width = width;
+                    info.mode_height = height;
+                }
+                wl_output::Event::Scale { factor } => info.scale = factor,
+                wl_output::Event::Name { name } => info.name = Some(name),
+                wl_output::Event::Description { description } => {
+                    info.description = Some(description)
+                }
+                _ => {}
+            }
+        }
+    });
+    info
+}
+
+impl OutputInfo {
+    /// The area this output covers in the global compositor space.
+    ///
+    /// Without xdg-output the size is estimated from the mode, scale and transform, which is
+    /// wrong for fractional scaling.
+    pub fn logical_region(&self) -> Region {
+        let (width, height) = if self.logical_width > 0 && self.logical_height > 0 {
+            (self.logical_width, self.logical_height)
+        } else {
+            let scale = self.scale.max(1);
+            let (width, height) = (self.mode_width / scale, self.mode_height / scale);
+            if self.is_rotated() {
+                (height, width)
+            } else {
+                (width, height)
+            }
+        };
+        Region {
+            x: self.logical_x,
+            y: self.logical_y,
+            width,
+            height,
+        }
+    }
+
+    /// Whether the transform turns the output by a quarter, swapping its width and height.
+    pub fn is_rotated(&self) -> bool {
+        matches!(
+            self.transform,
+            Some(
+                wl_output::Transform::_90
+                    | wl_output::Transform::_270
+                    | wl_output::Transform::Flipped90
+                    | wl_output::Transform::Flipped270
+            )
+        )
+    }
+
+    /// Horizontal and vertical pixels per inch of the upright image, if the output reports a
+    /// physical size.
+    ///
+    /// The mode and physical size are given in the output's hardware orientation, so they are
+    /// swapped for rotated outputs.
+    pub fn dpi(&self) -> Option<(u16, u16)> {
+        if self.physical_width <= 0 || self.physical_height <= 0 || self.mode_width <= 0 {
+            return None;
+        }
+        let density = |pixels: i32, mm: i32| {
+            (pixels as f64 * MM_PER_INCH / mm as f64)
+                .round()
+                .clamp(1.0, u16::MAX as f64) as u16
+        };
+        let horizontal = density(self.mode_width, self.physical_width);
+        let vertical = density(self.mode_height, self.physical_height);
+        if self.is_rotated() {
+            Some((vertical, horizontal))
+        } else {
+            Some((horizontal, vertical))
+        }
+    }
+}
+
+/// The line printed for this output by --list-outputs.
+impl fmt::Display for OutputInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}x{}, scale {}",
+            self.name.as_deref().unwrap_or("<unnamed>"),
+            self.mode_width,
+            self.mode_height,
+            self.scale
+        )?;
+        if let Some(transform) = self.transform {
+            write!(f, ", transform {}", transform_name(transform))?;
+        }
+        if let Some(subpixel) = self.subpixel {
+            write!(f, ", subpixel {}", subpixel_name(subpixel))?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, " ({})", description)?;
+        }
+        Ok(())
+    }
+}
+
+fn transform_name(transform: wl_output::Transform) -> &'static str {
+    match transform {
+        wl_output::Transform::Normal => "normal",
+        wl_output::Transform::_90 => "90",
+        wl_output::Transform::_180 => "180",
+        wl_output::Transform::_270 => "270",
+        wl_output::Transform::Flipped => "flipped",
+        wl_output::Transform::Flipped90 => "flipped-90",
+        wl_output::Transform::Flipped180 => "flipped-180",
+        wl_output::Transform::Flipped270 => "flipped-270",
+        _ => "unknown",
+    }
+}
+
+fn subpixel_name(subpixel: wl_output::Subpixel) -> &'static str {
+    match subpixel {
+        wl_output::Subpixel::None => "none",
+        wl_output::Subpixel::HorizontalRgb => "horizontal-rgb",
+        wl_output::Subpixel::HorizontalBgr => "horizontal-bgr",
+        wl_output::Subpixel::VerticalRgb => "vertical-rgb",
+        wl_output::Subpixel::VerticalBgr => "vertical-bgr",
+        _ => "unknown",
+    }
+}
+
+/// Whether the compositor reports the output as powered off (e.g. by DPMS).
+///
+/// Returns false when the compositor refuses to report the power state, which it does if
+/// another client currently controls the output's power.
+pub fn is_powered_off(
+    event_queue: &mut EventQueue,
+    power_manager: &Main<ZwlrOutputPowerManagerV1>,
+    output: &WlOutput,
+) -> bool {
+    let (output_power, power) = track_power(power_manager, output);
+    let roundtrip = event_queue.sync_roundtrip(&mut (), |_, _, _| {});
+    output_power.destroy();
+    roundtrip.is_ok() && power.get() == PowerState::Mode(zwlr_output_power_v1::Mode::Off)
+}
+
+/// Turn the output on, waiting until the compositor confirms it.
+///
+/// The returned object keeps control of the output's power; pass it to [`put_to_sleep`] once
+/// the capture is done. `None` means the compositor refused, e.g. because another client
+/// controls the output's power.
+pub fn wake(
+    event_queue: &mut EventQueue,
+    power_manager: &Main<ZwlrOutputPowerManagerV1>,
+    output: &WlOutput,
+) -> Option<Main<ZwlrOutputPowerV1>> {
+    let (output_power, power) = track_power(power_manager, output);
+    output_power.set_mode(zwlr_output_power_v1::Mode::On);
+    loop {
+        match power.get() {
+            PowerState::Mode(zwlr_output_power_v1::Mode::On) => return Some(output_power),
+            PowerState::Failed => break,
+            _ => {}
+        }
+        if event_queue.dispatch(&mut (), |_, _, _| {}).is_err() {
+            break;
+        }
+    }
+    output_power.destroy();
+    None
+}
+
+/// Turn an output woken by [`wake`] off again.
+pub fn put_to_sleep(output_power: Main<ZwlrOutputPowerV1>) {
+    output_power.set_mode(zwlr_output_power_v1::Mode::Off);
+    output_power.destroy();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PowerState {
+    Unknown,
+    Mode(zwlr_output_power_v1::Mode),
+    Failed,
+}
+
+fn track_power(
+    power_manager: &Main<ZwlrOutputPowerManagerV1>,
+    output: &WlOutput,
+) -> (Main<ZwlrOutputPowerV1>, Rc<Cell<PowerState>>) {
+    let power = Rc::new(Cell::new(PowerState::Unknown));
+    let output_power = power_manager.get_output_power(output);
+    output_power.quick_assign({
+        let power = power.clone();
+        move |_, event, _| match event {
+            zwlr_output_power_v1::Event::Mode { mode } => power.set(PowerState::Mode(mode)),
+            zwlr_output_power_v1::Event::Failed => power.set(PowerState::Failed),
+            _ => {}
+        }
+    });
+    (output_power, power)
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A rectangle in the compositor's logical coordinate space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Region {
+    pub fn contains(&self, other: &Region) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// The area covered by both regions, if they overlap.
+    pub fn intersection(&self, other: &Region) -> Option<Region> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        if right <= x as i64 || bottom <= y as i64 {
+            return None;
+        }
+        Some(Region {
+            x,
+            y,
+            width: (right - x as i64) as i32,
+            height: (bottom - y as i64) as i32,
+        })
+    }
+
+    /// The smallest region containing both.
+    pub fn union(&self, other: &Region) -> Region {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+        Region {
+            x,
+            y,
+            width: (right - x as i64).min(i32::MAX as i64) as i32,
+            height: (bottom - y as i64).min(i32::MAX as i64) as i32,
+        }
+    }
+
+    fn right(&self) -> i64 {
+        self.x as i64 + self.width as i64
+    }
+
+    fn bottom(&self) -> i64 {
+        self.y as i64 + self.height as i64
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{} {}x{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Parses the `x,y WxH` format printed by slurp.
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid geometry {:?}, expected \"x,y WxH\"", s);
+
+        let (x, rest) = s.split_once(',').ok_or_else(invalid)?;
+        let (y, size) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+
+        let parse = |value: &str| value.trim().parse::<i32>().map_err(|_| invalid());
+        let region = Region {
+            x: parse(x)?,
+            y: parse(y)?,
+            width: parse(width)?,
+            height: parse(height)?,
+        };
+
+        if region.width <= 0 || region.height <= 0 {
+            return Err(format!("Geometry {:?} has an empty size", s));
+        }
+        Ok(region)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: i32, y: i32, width: i32, height: i32) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn parses_slurp_output() {
+        assert_eq!("10,20 300x200".parse(), Ok(region(10, 20, 300, 200)));
+        assert_eq!("10,20 300x200\n".parse(), Ok(region(10, 20, 300, 200)));
+    }
+
+    #[test]
+    fn parses_negative_origins() {
+        assert_eq!("-1920,-5 100x50".parse(), Ok(region(-1920, -5, 100, 50)));
+    }
+
+    #[test]
+    fn tolerates_extra_spaces() {
+        assert_eq!("  10, 20  300 x 200 ".parse(), Ok(region(10, 20, 300, 200)));
+    }
+
+    #[test]
+    fn rejects_empty_and_negative_sizes() {
+        assert!("0,0 0x10".parse::<Region>().is_err());
+        assert!("0,0 10x0".parse::<Region>().is_err());
+        assert!("0,0 -10x10".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for input in [
+            "",
+            "10,20 300200",
+            "10 300x200",
+            "10,20",
+            "a,b cxd",
+            "0,0 1x1 extra",
+        ] {
+            assert!(input.parse::<Region>().is_err(), "{:?} parsed", input);
+        }
+    }
+
+    #[test]
+    fn contains_its_own_edges() {
+        let output = region(0, 0, 1920, 1080);
+        assert!(output.contains(&output));
+        assert!(output.contains(&region(1919, 1079, 1, 1)));
+        assert!(!output.contains(&region(1919, 1079, 2, 1)));
+        assert!(!output.contains(&region(-1, 0, 10, 10)));
+    }
+
+    #[test]
+    fn contains_does_not_overflow() {
+        let output = region(i32::MAX - 10, 0, 10, 10);
+        assert!(output.contains(&region(i32::MAX - 10, 0, 10, 10)));
+        assert!(!output.contains(&region(i32::MAX - 5, 0, i32::MAX, 10)));
+        let everything = region(i32::MIN, i32::MIN, i32::MAX, i32::MAX);
+        assert!(!everything.contains(&region(0, 0, i32::MAX, 1)));
+    }
+
+    #[test]
+    fn intersection_of_overlapping_regions() {
+        let left = region(0, 0, 1920, 1080);
+        let right = region(1920, 0, 1280, 1024);
+        let across = region(1800, 100, 300, 200);
+        assert_eq!(
+            left.intersection(&across),
+            Some(region(1800, 100, 120, 200))
+        );
+        assert_eq!(
+            right.intersection(&across),
+            Some(region(1920, 100, 180, 200))
+        );
+        assert_eq!(across.intersection(&across), Some(across));
+    }
+
+    #[test]
+    fn touching_regions_do_not_intersect() {
+        let left = region(0, 0, 1920, 1080);
+        assert_eq!(left.intersection(&region(1920, 0, 100, 100)), None);
+        assert_eq!(left.intersection(&region(0, -100, 100, 100)), None);
+    }
+
+    #[test]
+    fn union_is_the_bounding_box() {
+        let left = region(0, 0, 1920, 1080);
+        let right = region(1920, -200, 1280, 1024);
+        assert_eq!(left.union(&right), region(0, -200, 3200, 1280));
+        assert_eq!(
+            region(i32::MIN, 0, 1, 1)
+                .union(&region(i32::MAX - 1, 0, 1, 1))
+                .width,
+            i32::MAX
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io;
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+#[cfg(not(target_os = "freebsd"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use memmap2::Mmap;
+use nix::errno::Errno;
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+use nix::fcntl;
+#[cfg(not(target_os = "freebsd"))]
+use nix::fcntl::OFlag;
+#[cfg(target_os = "linux")]
+use nix::fcntl::{FallocateFlags, FcntlArg, SealFlag};
+#[cfg(target_os = "linux")]
+use nix::sys::memfd::{self, MemFdCreateFlag};
+use nix::sys::mman::{self, MmapAdvise};
+#[cfg(not(target_os = "freebsd"))]
+use nix::sys::stat::Mode;
+
+/// Create an anonymous shared memory file of `size` bytes for a wl_shm pool.
+///
+/// The blocks are allocated upfront so the compositor's copy does not page-fault its way
+/// through freshly extended, sparse memory, and so running out of shared memory is reported
+/// here instead of as a SIGBUS on the first write past the available space.
+pub fn create_shm_file(size: u64) -> io::Result<File> {
+    let file = create_anonymous_file()?;
+    match allocate(&file, size) {
+        Ok(()) => {
+            seal_size(&file);
+            Ok(file)
+        }
+        Err(Errno::ENOSPC) => Err(io::Error::other(format!(
+            "insufficient shared memory (need {} MiB)",
+            size.div_ceil(1 << 20)
+        ))),
+        // Not every backing filesystem can preallocate; a sparse file still works.
+        Err(Errno::EOPNOTSUPP) => {
+            file.set_len(size)?;
+            seal_size(&file);
+            Ok(file)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn allocate(file: &File, size: u64) -> nix::Result<()> {
+    fcntl::fallocate(
+        file.as_raw_fd(),
+        FallocateFlags::empty(),
+        0,
+        size as nix::libc::off_t,
+    )
+}
+
+#[cfg(target_os = "freebsd")]
+fn allocate(file: &File, size: u64) -> nix::Result<()> {
+    match fcntl::posix_fallocate(file.as_raw_fd(), 0, size as nix::libc::off_t) {
+        // FreeBSD reports filesystems and shm objects without preallocation support as EINVAL
+        // or ENODEV rather than EOPNOTSUPP.
+        Err(Errno::EINVAL) | Err(Errno::ENODEV) => Err(Errno::EOPNOTSUPP),
+        result => result,
+    }
+}
+
+/// The other BSDs have no posix_fallocate, so the file is always extended sparsely.
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+fn allocate(_file: &File, _size: u64) -> nix::Result<()> {
+    Err(Errno::EOPNOTSUPP)
+}
+
+#[cfg(target_os = "linux")]
+fn create_anonymous_file() -> io::Result<File> {
+    match memfd::memfd_create(
+        c"wayshot",
+        MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+    ) {
+        Ok(fd) => Ok(unsafe { File::from_raw_fd(fd) }),
+        // Kernels older than 3.17 have no memfd.
+        Err(Errno::ENOSYS) => create_unlinked_shm(),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// SHM_ANON gives an anonymous object directly, with no name to race on or clean up.
+#[cfg(target_os = "freebsd")]
+fn create_anonymous_file() -> io::Result<File> {
+    let fd = unsafe {
+        nix::libc::shm_open(
+            nix::libc::SHM_ANON,
+            nix::libc::O_RDWR | nix::libc::O_CLOEXEC,
+            0o600,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+fn create_anonymous_file() -> io::Result<File> {
+    create_unlinked_shm()
+}
+
+/// A POSIX shm object that is unlinked straight away, so nothing is left behind once the fd
+/// is closed.
+#[cfg(not(target_os = "freebsd"))]
+fn create_unlinked_shm() -> io::Result<File> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or_default();
+    let name = format!("/wayshot-{}-{}", std::process::id(), nanos);
+    let fd = mman::shm_open(
+        name.as_str(),
+        OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_RDWR | OFlag::O_CLOEXEC,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+    )?;
+    let _ = mman::shm_unlink(name.as_str());
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Freeze the size of a memfd so nobody holding the fd can truncate it underneath our
+/// mapping. Shm objects from the fallback path cannot be sealed, which is fine.
+///
+/// F_SEAL_WRITE is deliberately not applied: the compositor keeps a writable mapping of the
+/// pool until it has processed the destroy requests, so the seal would fail with EBUSY.
+#[cfg(target_os = "linux")]
+fn seal_size(file: &File) {
+    let _ = fcntl::fcntl(
+        file.as_raw_fd(),
+        FcntlArg::F_ADD_SEALS(
+            SealFlag::F_SEAL_SHRINK | SealFlag::F_SEAL_GROW | SealFlag::F_SEAL_SEAL,
+        ),
+    );
+}
+
+/// Only memfds support seals.
+#[cfg(not(target_os = "linux"))]
+fn seal_size(_file: &File) {}
+
+/// Tell the kernel the mapping is about to be read once, front to back.
+///
+/// Advice is only a hint, so failures (e.g. transparent huge pages disabled for shmem) are
+/// ignored.
+pub fn advise_sequential_read(mmap: &Mmap) {
+    let addr = mmap.as_ptr() as *mut nix::libc::c_void;
+    for advice in [
+        MmapAdvise::MADV_SEQUENTIAL,
+        MmapAdvise::MADV_WILLNEED,
+        #[cfg(target_os = "linux")]
+        MmapAdvise::MADV_HUGEPAGE,
+    ] {
+        let _ = unsafe { mman::madvise(addr, mmap.len(), advice) };
+    }
+}
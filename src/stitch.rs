@@ -0,0 +1,280 @@
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+
+use crate::region::Region;
+
+/// The largest canvas we are willing to allocate, in bytes. Eight 8K outputs side by side
+/// need under 800 MiB, so only a sparse layout of far-apart outputs gets anywhere near this.
+const MAX_CANVAS_BYTES: u64 = 4 << 30;
+
+/// The resampling filter named by --filter-scale.
+pub fn filter_from_name(name: &str) -> Option<FilterType> {
+    match name {
+        "nearest" => Some(FilterType::Nearest),
+        "bilinear" => Some(FilterType::Triangle),
+        "catmull-rom" => Some(FilterType::CatmullRom),
+        "lanczos3" => Some(FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+/// Composite upright images into one following their logical layout. Each image comes with
+/// the area of the compositor space it shows, which is a whole output or part of one.
+///
+/// The canvas uses the highest pixel density among the images, so the sharpest output is
+/// kept at native resolution and lower-density ones are scaled up to match. Gaps between
+/// outputs stay black.
+///
+/// `filter` is used to scale images up. Without one, whole-number factors use nearest
+/// neighbour and anything else bilinear filtering.
+pub fn stitch(
+    captures: Vec<(Region, RgbImage)>,
+    filter: Option<FilterType>,
+) -> Result<RgbImage, String> {
+    let captures: Vec<_> = captures
+        .into_iter()
+        .filter(|(region, _)| region.width > 0 && region.height > 0)
+        .collect();
+
+    if captures.is_empty() {
+        return Err("None of the outputs has a usable logical size".to_string());
+    }
+
+    let scale = captures
+        .iter()
+        .map(|(region, image)| image.width() as f64 / region.width as f64)
+        .fold(1.0, f64::max);
+
+    let min_x = captures.iter().map(|(r, _)| r.x as i64).min().unwrap();
+    let min_y = captures.iter().map(|(r, _)| r.y as i64).min().unwrap();
+    let max_x = captures
+        .iter()
+        .map(|(r, _)| r.x as i64 + r.width as i64)
+        .max()
+        .unwrap();
+    let max_y = captures
+        .iter()
+        .map(|(r, _)| r.y as i64 + r.height as i64)
+        .max()
+        .unwrap();
+
+    let to_pixels = |logical: i64| (logical as f64 * scale).round() as u64;
+    let width = to_pixels(max_x - min_x);
+    let height = to_pixels(max_y - min_y);
+    let bytes = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(3));
+    let too_large = || {
+        format!(
+            "The combined {}x{} image of all outputs is too large",
+            width, height
+        )
+    };
+    if width > u32::MAX as u64
+        || height > u32::MAX as u64
+        || bytes.is_none_or(|bytes| bytes > MAX_CANVAS_BYTES)
+    {
+        return Err(too_large());
+    }
+
+    // Below the ceiling the allocation can still fail on a small machine; report that rather
+    // than aborting.
+    let bytes = bytes.unwrap() as usize;
+    let mut pixels = Vec::new();
+    pixels.try_reserve_exact(bytes).map_err(|_| too_large())?;
+    pixels.resize(bytes, 0);
+    let mut canvas = RgbImage::from_raw(width as u32, height as u32, pixels).unwrap();
+    for (region, image) in captures {
+        let target_width = to_pixels(region.width as i64) as u32;
+        let target_height = to_pixels(region.height as i64) as u32;
+
+        let image = if image.dimensions() == (target_width, target_height) {
+            image
+        } else {
+            // Whole-number upscales (a scale 1 output next to a scale 2 one) stay crisp.
+            let filter = filter.unwrap_or(
+                if target_width.is_multiple_of(image.width())
+                    && target_height.is_multiple_of(image.height())
+                {
+                    FilterType::Nearest
+                } else {
+                    FilterType::Triangle
+                },
+            );
+            imageops::resize(&image, target_width, target_height, filter)
+        };
+
+        imageops::replace(
+            &mut canvas,
+            &image,
+            to_pixels(region.x as i64 - min_x) as i64,
+            to_pixels(region.y as i64 - min_y) as i64,
+        );
+    }
+    Ok(canvas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+
+    fn capture(
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        pixels: (u32, u32),
+        colour: Rgb<u8>,
+    ) -> (Region, RgbImage) {
+        let region = Region {
+            x,
+            y,
+            width,
+            height,
+        };
+        (region, RgbImage::from_pixel(pixels.0, pixels.1, colour))
+    }
+
+    #[test]
+    fn mixed_scales_use_the_highest_density() {
+        // A scale 1 output next to a scale 2 one half its logical size.
+        let canvas = stitch(
+            vec![
+                capture(0, 0, 64, 48, (64, 48), RED),
+                capture(64, 0, 16, 16, (32, 32), BLUE),
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(canvas.dimensions(), (160, 96));
+        assert_eq!(*canvas.get_pixel(127, 95), RED);
+        assert_eq!(*canvas.get_pixel(128, 0), BLUE);
+        assert_eq!(*canvas.get_pixel(159, 31), BLUE);
+        assert_eq!(*canvas.get_pixel(128, 32), BLACK);
+    }
+
+    #[test]
+    fn gaps_stay_black() {
+        let canvas = stitch(
+            vec![
+                capture(0, 0, 10, 10, (10, 10), RED),
+                capture(20, 0, 10, 10, (10, 10), BLUE),
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(canvas.dimensions(), (30, 10));
+        assert_eq!(*canvas.get_pixel(9, 9), RED);
+        assert_eq!(*canvas.get_pixel(15, 5), BLACK);
+        assert_eq!(*canvas.get_pixel(20, 0), BLUE);
+    }
+
+    #[test]
+    fn negative_origins_are_shifted_onto_the_canvas() {
+        let canvas = stitch(
+            vec![
+                capture(-10, -5, 10, 10, (10, 10), RED),
+                capture(0, 0, 10, 10, (10, 10), BLUE),
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(canvas.dimensions(), (20, 15));
+        assert_eq!(*canvas.get_pixel(0, 0), RED);
+        assert_eq!(*canvas.get_pixel(9, 9), RED);
+        assert_eq!(*canvas.get_pixel(10, 4), BLACK);
+        assert_eq!(*canvas.get_pixel(10, 5), BLUE);
+        assert_eq!(*canvas.get_pixel(19, 14), BLUE);
+    }
+
+    #[test]
+    fn empty_regions_are_skipped() {
+        let canvas = stitch(
+            vec![
+                capture(0, 0, 0, 0, (4, 4), BLUE),
+                capture(10, 10, 4, 4, (4, 4), RED),
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(canvas.dimensions(), (4, 4));
+        assert!(stitch(vec![capture(0, 0, 0, 0, (4, 4), BLUE)], None).is_err());
+    }
+
+    #[test]
+    fn oversized_canvas_is_an_error() {
+        let result = stitch(
+            vec![
+                capture(i32::MIN, i32::MIN, 1, 1, (1, 1), RED),
+                capture(i32::MAX - 1, i32::MAX - 1, 1, 1, (1, 1), BLUE),
+            ],
+            None,
+        );
+        assert!(result.unwrap_err().contains("too large"));
+    }
+
+    #[test]
+    fn far_apart_outputs_are_an_error() {
+        // 40000x40000 at 3 bytes per pixel is about 4.5 GiB: representable, but not sensible.
+        let result = stitch(
+            vec![
+                capture(0, 0, 1, 1, (1, 1), RED),
+                capture(39999, 39999, 1, 1, (1, 1), BLUE),
+            ],
+            None,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            "The combined 40000x40000 image of all outputs is too large"
+        );
+    }
+
+    /// A 2x2 checkerboard next to a 3x3 output of the same logical size, so the checkerboard
+    /// is scaled by 1.5.
+    fn fractional_upscale(filter: Option<FilterType>) -> RgbImage {
+        let checkerboard =
+            RgbImage::from_fn(2, 2, |x, y| if (x + y) % 2 == 0 { RED } else { BLUE });
+        let region = Region {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        let canvas = stitch(
+            vec![(region, checkerboard), capture(2, 0, 2, 2, (3, 3), BLACK)],
+            filter,
+        )
+        .unwrap();
+        assert_eq!(canvas.dimensions(), (6, 3));
+        imageops::crop_imm(&canvas, 0, 0, 3, 3).to_image()
+    }
+
+    #[test]
+    fn fractional_upscales_are_filtered_by_default() {
+        let scaled = fractional_upscale(None);
+        assert!(scaled.pixels().any(|pixel| *pixel != RED && *pixel != BLUE));
+    }
+
+    #[test]
+    fn explicit_filter_is_used() {
+        let scaled = fractional_upscale(Some(FilterType::Nearest));
+        assert!(scaled.pixels().all(|pixel| *pixel == RED || *pixel == BLUE));
+    }
+
+    #[test]
+    fn filter_names() {
+        assert_eq!(filter_from_name("nearest"), Some(FilterType::Nearest));
+        assert_eq!(filter_from_name("bilinear"), Some(FilterType::Triangle));
+        assert_eq!(
+            filter_from_name("catmull-rom"),
+            Some(FilterType::CatmullRom)
+        );
+        assert_eq!(filter_from_name("lanczos3"), Some(FilterType::Lanczos3));
+        assert_eq!(filter_from_name("gaussian"), None);
+    }
+}